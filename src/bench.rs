@@ -96,3 +96,59 @@ compare_box_and_arena!(allocating_128_bytes, 128);
 compare_box_and_arena!(allocating_256_bytes, 256);
 compare_box_and_arena!(allocating_512_bytes, 512);
 compare_box_and_arena!(allocating_1024_bytes, 1024);
+
+macro_rules! compare_arena_fast_path_and_extend {
+    ($name: ident, $bytes: expr) => {
+        mod $name {
+            extern crate test;
+            use ::Arena;
+
+            const DUMMY_BYTE: u8 = 0x55;
+            const ALLOCATION_COUNT: usize = 10_000;
+
+            // The arena is pre-sized for one `b.iter()` pass' worth of
+            // allocations, so the *first* pass exercises `alloc`'s
+            // pointer-bump fast path (`ptr != end`) exclusively. `Bencher`
+            // calls the closure many more times than once to calibrate,
+            // though, and this same arena is reused across all of them
+            // (same preexisting habit as `compare_box_and_arena!` above), so
+            // later passes also take the `RefCell`-guarded `grow` cold path
+            // whenever the current chunk runs out; since each new chunk
+            // doubles in size, that happens at most once per pass. Still useful as a sanity check that the cursor
+            // rewrite of `alloc` (replacing the old `Vec<Vec<T>>` push)
+            // doesn't regress either path.
+
+            #[bench]
+            fn with_arena_alloc(b: &mut test::Bencher) {
+                let arena = Arena::with_capacity(ALLOCATION_COUNT);
+                let mut saved = Vec::with_capacity(ALLOCATION_COUNT);
+
+                b.iter(|| {
+                    for _ in 0..ALLOCATION_COUNT {
+                        saved.push(test::black_box(arena.alloc([DUMMY_BYTE; $bytes])));
+                    }
+                })
+            }
+
+            // `alloc_extend` bump-allocates the whole batch in one pass
+            // rather than one item at a time, so it should beat an
+            // equivalent loop of individual `alloc` calls.
+            #[bench]
+            fn with_arena_alloc_extend(b: &mut test::Bencher) {
+                let arena = Arena::with_capacity(ALLOCATION_COUNT);
+                let mut saved = Vec::with_capacity(ALLOCATION_COUNT);
+
+                b.iter(|| {
+                    let items = (0..ALLOCATION_COUNT).map(|_| [DUMMY_BYTE; $bytes]);
+                    saved.push(test::black_box(arena.alloc_extend(items)));
+                })
+            }
+        }
+    };
+}
+
+compare_arena_fast_path_and_extend!(arena_fast_path_1_bytes, 1);
+compare_arena_fast_path_and_extend!(arena_fast_path_16_bytes, 16);
+compare_arena_fast_path_and_extend!(arena_fast_path_64_bytes, 64);
+compare_arena_fast_path_and_extend!(arena_fast_path_256_bytes, 256);
+compare_arena_fast_path_and_extend!(arena_fast_path_1024_bytes, 1024);
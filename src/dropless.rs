@@ -0,0 +1,126 @@
+//! A bump allocator for `Copy` types that do not need `Drop` to run.
+//!
+//! Unlike `Arena<T>`, which is monomorphized per `T` and backed by a
+//! `Vec<Vec<T>>`, a `DroplessArena` stores raw, differently-typed bytes in
+//! a handful of growing chunks and hands them out with pointer-bump speed.
+//! This suits workloads that allocate many small values of many different
+//! types that don't need their destructors run, such as interned symbols
+//! or small AST fragments, without paying for one arena per type.
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::mem;
+use std::ptr;
+use std::slice;
+
+// Initial size in bytes.
+const INITIAL_SIZE: usize = 1024;
+
+pub struct DroplessArena {
+    // The start of the unallocated space in the current chunk.
+    start: Cell<*mut u8>,
+    // The end of the current chunk. `start` is always <= `end`.
+    end: Cell<*mut u8>,
+    // All chunks allocated so far. The last one is the current chunk that
+    // `start` and `end` point into; the others are full and kept around
+    // only so the bytes they hold stay alive.
+    chunks: RefCell<Vec<Vec<u8>>>,
+}
+
+impl DroplessArena {
+    pub fn new() -> DroplessArena {
+        DroplessArena {
+            start: Cell::new(0 as *mut u8),
+            end: Cell::new(0 as *mut u8),
+            chunks: RefCell::new(vec![]),
+        }
+    }
+
+    #[inline(never)]
+    #[cold]
+    fn grow(&self, additional: usize) {
+        let mut chunks = self.chunks.borrow_mut();
+        let prev_capacity = chunks.last().map_or(0, |chunk| chunk.capacity());
+        let new_capacity = cmp::max(additional, cmp::max(INITIAL_SIZE, prev_capacity * 2));
+
+        let mut chunk = Vec::with_capacity(new_capacity);
+        let start = chunk.as_mut_ptr();
+
+        unsafe {
+            self.start.set(start);
+            self.end.set(start.offset(new_capacity as isize));
+        }
+
+        chunks.push(chunk);
+    }
+
+    // Bump-allocates `size` bytes aligned to `align`, starting a new chunk
+    // if there isn't enough room left in the current one.
+    #[inline]
+    fn alloc_raw(&self, size: usize, align: usize) -> *mut u8 {
+        loop {
+            let start = self.start.get() as usize;
+            let end = self.end.get() as usize;
+
+            let aligned_start = (start + align - 1) & !(align - 1);
+            let new_start = aligned_start.checked_add(size).unwrap();
+
+            if new_start <= end {
+                self.start.set(new_start as *mut u8);
+                return aligned_start as *mut u8;
+            }
+
+            // Doesn't fit in what's left of the current chunk: grow a new
+            // one large enough for this allocation (plus room to spare for
+            // alignment padding) and retry.
+            self.grow(size + align);
+        }
+    }
+
+    pub fn alloc<T>(&self, value: T) -> &mut T {
+        assert!(!mem::needs_drop::<T>());
+
+        // A zero-sized `T` never actually needs storage: `start`/`end` are
+        // still null on a fresh arena, and `alloc_raw`'s fit check would
+        // happily hand back that null pointer for a zero-size allocation.
+        // Use the dangling-but-aligned pointer `NonNull::dangling` would
+        // produce instead, without touching `start`/`end` at all.
+        if mem::size_of::<T>() == 0 {
+            let ptr = mem::align_of::<T>() as *mut T;
+            unsafe {
+                ptr::write(ptr, value);
+                return &mut *ptr;
+            }
+        }
+
+        let ptr = self.alloc_raw(mem::size_of::<T>(), mem::align_of::<T>()) as *mut T;
+
+        unsafe {
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    pub fn alloc_slice<T: Copy>(&self, slice: &[T]) -> &mut [T] {
+        assert!(!mem::needs_drop::<T>());
+
+        if slice.is_empty() {
+            return &mut [];
+        }
+
+        // See the comment in `alloc`: zero-sized elements need no storage,
+        // so don't ask `alloc_raw` to fit a zero-size allocation.
+        if mem::size_of::<T>() == 0 {
+            let ptr = mem::align_of::<T>() as *mut T;
+            return unsafe { slice::from_raw_parts_mut(ptr, slice.len()) };
+        }
+
+        let size = slice.len().checked_mul(mem::size_of::<T>()).unwrap();
+        let ptr = self.alloc_raw(size, mem::align_of::<T>()) as *mut T;
+
+        unsafe {
+            ptr::copy_nonoverlapping(slice.as_ptr(), ptr, slice.len());
+            slice::from_raw_parts_mut(ptr, slice.len())
+        }
+    }
+}
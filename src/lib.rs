@@ -1,35 +1,45 @@
 #![feature(placement_in_syntax, placement_new_protocol)]
 #![feature(test)]
 #![feature(box_syntax, box_heap)]
+#![feature(needs_drop)]
+#![feature(conservative_impl_trait)]
+#![feature(dropck_eyepatch)]
 
 //! The arena, a fast but limited type of allocator.
 //!
 //! Arenas are a type of allocator that destroy the objects within,
 //! all at once, once the arena itself is destroyed.
 //! They do not support deallocation of individual objects while the arena itself is still alive.
-//! The benefit of an arena is very fast allocation; just a vector push.
+//! The benefit of an arena is very fast allocation; just a pointer bump.
 //!
 //! This is an equivalent of
 //! [`arena::TypedArena`](http://doc.rust-lang.org/arena/struct.TypedArena.html)
 //! distributed with rustc, but is available of Rust beta/stable.
 //!
-//! It is slightly less efficient, but simpler internally and uses much less unsafe code.
-//! It is based on a `Vec<Vec<T>>` instead of raw pointers and manual drops.
+//! Chunks are backed by `Vec<T>`, but allocation itself bumps a pair of raw
+//! pointers into the current chunk's storage rather than going through
+//! `Vec::push`, so the common case touches no `RefCell` borrow at all.
 
 // Potential optimizations:
 // 1) add and stabilize a method for in-place reallocation of vecs.
 // 2) add and stabilize placement new.
-// 3) use an iterator. This may add far too much unsafe code.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::cmp;
+use std::iter;
 use std::mem;
+use std::ptr;
+use std::slice;
+use std::vec;
 
 #[cfg(test)]
 mod test;
 #[cfg(test)]
 mod bench;
 
+mod dropless;
+pub use dropless::DroplessArena;
+
 // Initial size in bytes.
 const INITIAL_SIZE: usize = 1024;
 // Minimum capacity. Must be larger than 0.
@@ -37,6 +47,11 @@ const MIN_CAPACITY: usize = 1;
 
 pub struct Arena<T> {
     chunks: RefCell<ChunkList<T>>,
+    // The bump-allocation cursor into `chunks.current`'s backing storage,
+    // and the address one past the end of that storage. `alloc` only needs
+    // to touch the `RefCell` when `ptr == end` and a new chunk is needed.
+    ptr: Cell<*mut T>,
+    end: Cell<*mut T>,
 }
 
 struct ChunkList<T> {
@@ -52,43 +67,118 @@ impl<T> Arena<T> {
 
     pub fn with_capacity(n: usize) -> Arena<T> {
         let n = cmp::max(MIN_CAPACITY, n);
+        let mut current = Vec::with_capacity(n);
+        let start = current.as_mut_ptr();
+        let end = unsafe { start.offset(n as isize) };
         Arena {
             chunks: RefCell::new(ChunkList {
-                current: Vec::with_capacity(n),
-                rest: vec![]
+                current: current,
+                rest: vec![],
             }),
+            ptr: Cell::new(start),
+            end: Cell::new(end),
         }
     }
 
+    #[inline]
     pub fn alloc(&self, value: T) -> &mut T {
-        // TODO: When placement syntax becomes stable, replace this method with
-        // in self { value }
+        if mem::size_of::<T>() == 0 {
+            return self.alloc_zst(value);
+        }
 
-        let mut chunks = self.chunks.borrow_mut();
+        let ptr = self.ptr.get();
+
+        if ptr == self.end.get() {
+            self.grow(1);
+        }
 
-        // At this point, the current chunk must have free capacity.
-        let next_item_index = chunks.current.len();
+        let ptr = self.ptr.get();
+        unsafe {
+            self.ptr.set(ptr.offset(1));
+            ptr::write(ptr, value);
+            &mut *ptr
+        }
+    }
+
+    // `ptr::offset` is a no-op for a zero-size step, so `end == start ==
+    // ptr` from construction on for a zero-sized `T`: the cursor can never
+    // tell "full" from "empty", and `grow`'s capacity-doubling would
+    // overflow against `Vec<T>::capacity() == usize::MAX`. Sidestep the
+    // cursor entirely: push straight into `current` (which never
+    // reallocates for a ZST) so `current.len()` alone is an accurate
+    // count, and hand back the same dangling-but-aligned pointer
+    // `DroplessArena` uses for its ZST case, since there's no actual data
+    // to address.
+    fn alloc_zst(&self, value: T) -> &mut T {
+        let mut chunks = self.chunks.borrow_mut();
         chunks.current.push(value);
-        let new_item_ref = {
-            let new_item_ref = &mut chunks.current[next_item_index];
-
-            // Extend the lifetime from that of `chunks_borrow` to that of `self`.
-            // This is OK because we’re careful to never move items
-            // by never pushing to inner `Vec`s beyond their initial capacity.
-            // The returned reference is unique (`&mut`):
-            // the `Arena` never gives away references to existing items.
-            unsafe { mem::transmute::<&mut T, &mut T>(new_item_ref) }
-        };
-
-        if chunks.current.len() == chunks.current.capacity() {
-            chunks.grow();
+        let ptr = mem::align_of::<T>() as *mut T;
+        unsafe { &mut *ptr }
+    }
+
+    /// Allocates space for, and fills it with, the items produced by
+    /// `iterable`. The returned slice is a reference to the now-allocated
+    /// space, and the items appear in the order in which the iterator
+    /// yielded them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if reserving space for the extension would cause an overflow.
+    pub fn alloc_extend<I>(&self, iterable: I) -> &mut [T]
+        where I: IntoIterator<Item = T>
+    {
+        if mem::size_of::<T>() == 0 {
+            // See `alloc_zst`: the cursor can't represent "how many ZSTs
+            // have been allocated," so push each item into `current`
+            // directly and count them as we go.
+            let mut chunks = self.chunks.borrow_mut();
+            let mut len = 0;
+            for item in iterable {
+                chunks.current.push(item);
+                len += 1;
+            }
+            let ptr = mem::align_of::<T>() as *mut T;
+            return unsafe { slice::from_raw_parts_mut(ptr, len) };
+        }
+
+        let mut iter = iterable.into_iter();
+
+        let iter_min_len = iter.size_hint().0;
+        let remaining = (self.end.get() as usize - self.ptr.get() as usize)
+            / cmp::max(1, mem::size_of::<T>());
+        if remaining < iter_min_len {
+            self.grow(iter_min_len);
+        }
+
+        let mut start = self.ptr.get();
+
+        loop {
+            let item = match iter.next() {
+                Some(item) => item,
+                None => break,
+            };
+
+            if self.ptr.get() == self.end.get() {
+                // The iterator yielded more items than its lower bound
+                // promised. Relocate what we've written so far into a new,
+                // larger chunk so the returned slice stays contiguous.
+                start = self.grow_carrying(start, 1);
+            }
+
+            let ptr = self.ptr.get();
+            unsafe {
+                ptr::write(ptr, item);
+                self.ptr.set(ptr.offset(1));
+            }
         }
 
-        new_item_ref
+        let len = (self.ptr.get() as usize - start as usize) / cmp::max(1, mem::size_of::<T>());
+        unsafe { slice::from_raw_parts_mut(start, len) }
     }
 
     pub fn into_vec(self) -> Vec<T> {
-        let mut chunks = self.chunks.into_inner();
+        let mut chunks = self.into_vec_chunks();
+
         // keep order of allocation in the resulting Vec
         let n = chunks.rest.iter().fold(chunks.current.len(), |a, v| a + v.len());
         let mut result = Vec::with_capacity(n);
@@ -98,16 +188,237 @@ impl<T> Arena<T> {
         result.append(&mut chunks.current);
         result
     }
-}
 
-impl<T> ChunkList<T> {
+    /// Returns the number of items allocated so far.
+    pub fn len(&self) -> usize {
+        let chunks = self.chunks.borrow();
+        let current_len = self.current_len(&chunks);
+        chunks.rest.iter().fold(current_len, |a, v| a + v.len())
+    }
+
+    /// Iterates over the items allocated so far, in allocation order.
+    ///
+    /// Taking `&mut self` proves there are no outstanding shared references
+    /// to items in the arena, so handing out `&mut T` here is safe without
+    /// the raw-pointer tricks `alloc` relies on.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        let filled = self.current_len(&self.chunks.borrow());
+        let chunks = self.chunks.get_mut();
+        unsafe { chunks.current.set_len(filled) };
+        chunks.rest.iter_mut().flat_map(|v| v.iter_mut()).chain(chunks.current.iter_mut())
+    }
+
+    /// Like `alloc`, but returns a relocatable `ArenaRef` handle instead of
+    /// a borrow. The handle can be freely copied and embedded in other
+    /// data without entangling its lifetime with the arena's, and is
+    /// resolved back to a `&T` through `AsArena::get`.
+    pub fn alloc_ref(&self, value: T) -> ArenaRef<T> {
+        ArenaRef::new(self.alloc(value) as *const T)
+    }
+
+    // Returns how many items of `current` (the still-open chunk) have
+    // actually been initialized, computed from the bump cursor rather than
+    // `current.len()`, which is only kept in sync when a chunk is
+    // finalized (pushed to `rest`), in `iter_mut`, or when the arena is
+    // dropped or consumed.
+    //
+    // For a zero-sized `T` the cursor never moves (see `alloc_zst`), so
+    // `current.len()` is the only source of truth there — and it's always
+    // accurate, since the ZST paths push directly into `current` instead
+    // of relying on the cursor.
+    fn current_len(&self, chunks: &ChunkList<T>) -> usize {
+        if mem::size_of::<T>() == 0 {
+            return chunks.current.len();
+        }
+
+        let start = chunks.current.as_ptr() as usize;
+        let ptr = self.ptr.get() as usize;
+        (ptr - start) / mem::size_of::<T>()
+    }
+
+    // The cold path taken once the current chunk is exhausted: finalize it
+    // and bump-allocate a fresh one with room for at least `additional`
+    // more items.
     #[inline(never)]
     #[cold]
-    fn grow(&mut self) {
-        // Replace the current chunk with a newly allocated chunk.
-        let new_capacity = self.current.capacity().checked_mul(2).unwrap();
-        let chunk = mem::replace(&mut self.current, Vec::with_capacity(new_capacity));
-        self.rest.push(chunk);
+    fn grow(&self, additional: usize) {
+        let ptr = self.ptr.get();
+        self.grow_carrying(ptr, additional);
+    }
+
+    // Finalizes the current chunk and allocates a new one, relocating the
+    // `[carry_start, self.ptr)` tail of the old chunk to the front of the
+    // new one so a slice spanning the boundary (as `alloc_extend` can
+    // produce) stays contiguous. Returns the new chunk's start pointer,
+    // i.e. where the carried-over items now live.
+    fn grow_carrying(&self, carry_start: *mut T, additional: usize) -> *mut T {
+        let mut chunks = self.chunks.borrow_mut();
+
+        let elem_size = cmp::max(1, mem::size_of::<T>());
+        let old_start = chunks.current.as_ptr() as usize;
+        let carried = (self.ptr.get() as usize - carry_start as usize) / elem_size;
+        let kept = (carry_start as usize - old_start) / elem_size;
+
+        // The old chunk keeps only the items before `carry_start`; the
+        // carried-over tail is about to be duplicated into the new chunk,
+        // so excluding it here is what prevents those items being dropped
+        // twice.
+        unsafe { chunks.current.set_len(kept) };
+
+        let double_cap = chunks.current.capacity().checked_mul(2).unwrap();
+        let new_capacity = cmp::max(carried + additional, double_cap);
+        let mut new_chunk = Vec::with_capacity(new_capacity);
+        let new_start = new_chunk.as_mut_ptr();
+
+        if carried > 0 {
+            unsafe { ptr::copy_nonoverlapping(carry_start, new_start, carried) };
+        }
+
+        self.ptr.set(unsafe { new_start.offset(carried as isize) });
+        self.end.set(unsafe { new_start.offset(new_capacity as isize) });
+
+        let old_chunk = mem::replace(&mut chunks.current, new_chunk);
+        chunks.rest.push(old_chunk);
+
+        new_start
+    }
+
+    // Shared by `into_vec` and `IntoIterator::into_iter`: takes ownership of
+    // the chunk list with `current`'s length fixed up, without running
+    // `Arena`'s `Drop` impl.
+    fn into_vec_chunks(self) -> ChunkList<T> {
+        let filled = self.current_len(&self.chunks.borrow());
+        let mut chunks = unsafe { ptr::read(&self.chunks).into_inner() };
+        mem::forget(self);
+        unsafe { chunks.current.set_len(filled) };
+        chunks
+    }
+}
+
+// The eyepatch on `T` tells the dropck that this impl will not access any
+// `&'a _` borrowed from items it drops, which is what lets callers build
+// cyclic graphs of `Drop` types that reference their siblings through
+// `&'a T` fields allocated in the same arena: without it, the borrow
+// checker would conservatively assume `Arena`'s `Drop` might dereference
+// those references during its own teardown, and reject the cycle.
+unsafe impl<#[may_dangle] T> Drop for Arena<T> {
+    fn drop(&mut self) {
+        // Fix up the current chunk's length to the number of items actually
+        // initialized, so that its `Vec`'s own `Drop` impl runs their
+        // destructors (and only theirs) before freeing the backing memory.
+        let mut chunks = self.chunks.borrow_mut();
+        let filled = self.current_len(&chunks);
+        unsafe { chunks.current.set_len(filled) };
+    }
+}
+
+fn vec_into_iter<T>(v: Vec<T>) -> vec::IntoIter<T> {
+    v.into_iter()
+}
+
+impl<T> IntoIterator for Arena<T> {
+    type Item = T;
+    type IntoIter = iter::Chain<
+        iter::FlatMap<vec::IntoIter<Vec<T>>, vec::IntoIter<T>, fn(Vec<T>) -> vec::IntoIter<T>>,
+        vec::IntoIter<T>,
+    >;
+
+    /// Iterates over the items allocated so far, in allocation order,
+    /// consuming the arena.
+    fn into_iter(self) -> Self::IntoIter {
+        let chunks = self.into_vec_chunks();
+        chunks.rest.into_iter()
+            .flat_map(vec_into_iter as fn(Vec<T>) -> vec::IntoIter<T>)
+            .chain(chunks.current.into_iter())
+    }
+}
+
+/// A stable handle to a value allocated in an `Arena`, as an alternative to
+/// the borrow that `alloc` returns.
+///
+/// Unlike `&T`, an `ArenaRef<T>` doesn't borrow the arena, so it can be
+/// copied freely and stored inside other data (for example, nodes in a
+/// graph that reference their siblings) without entangling the lifetimes
+/// of the handle and the arena. Resolve it back to a `&T` with
+/// `AsArena::get`, which ties the returned reference to the arena borrow
+/// at lookup time rather than at allocation time.
+///
+/// Because a handle carries no lifetime of its own, nothing stops it from
+/// outliving the arena that produced it. `get` checks that a handle falls
+/// within the *target* arena's chunks, which catches resolving it against
+/// the wrong live arena, but it cannot detect the arena it actually came
+/// from having already been dropped — resolving a handle after that
+/// arena is gone is a use-after-free that this type does not guard
+/// against.
+pub struct ArenaRef<T> {
+    ptr: *const T,
+}
+
+impl<T> ArenaRef<T> {
+    fn new(ptr: *const T) -> ArenaRef<T> {
+        ArenaRef { ptr: ptr }
+    }
+}
+
+impl<T> Clone for ArenaRef<T> {
+    fn clone(&self) -> ArenaRef<T> {
+        *self
+    }
+}
+
+impl<T> Copy for ArenaRef<T> {}
+
+/// Resolves `ArenaRef` handles back into references borrowed from the
+/// arena that produced them.
+pub trait AsArena<T> {
+    /// Confirms that `handle_ptr` actually falls within one of this
+    /// arena's chunks, to catch a handle accidentally resolved against the
+    /// wrong arena.
+    ///
+    /// This check runs unconditionally, including in release builds,
+    /// because `get` relies on it for memory safety rather than as a
+    /// debugging aid. It cannot, however, detect a handle resolved after
+    /// *its own* originating arena has already been dropped: if that
+    /// arena's freed memory happens to be reused by `self`'s own chunks,
+    /// the check sees an address within `self`'s ranges and passes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `handle_ptr` does not fall within any chunk of this
+    /// arena.
+    fn verify_ownership(&self, handle_ptr: *const T);
+
+    /// Resolves `handle` to a reference borrowed from this arena.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `handle` was not allocated from this arena (see
+    /// `verify_ownership`).
+    fn get<'a>(&'a self, handle: &ArenaRef<T>) -> &'a T;
+}
+
+impl<T> AsArena<T> for Arena<T> {
+    fn verify_ownership(&self, handle_ptr: *const T) {
+        assert!(
+            {
+                let chunks = self.chunks.borrow();
+                let current_start = chunks.current.as_ptr();
+                let current_end = self.ptr.get() as *const T;
+                let in_current = handle_ptr >= current_start && handle_ptr < current_end;
+                let in_rest = chunks.rest.iter().any(|v| {
+                    let start = v.as_ptr();
+                    let end = unsafe { start.offset(v.len() as isize) };
+                    handle_ptr >= start && handle_ptr < end
+                });
+                in_current || in_rest
+            },
+            "ArenaRef does not belong to this Arena"
+        );
+    }
+
+    fn get<'a>(&'a self, handle: &ArenaRef<T>) -> &'a T {
+        self.verify_ownership(handle.ptr);
+        unsafe { &*handle.ptr }
     }
 }
 
@@ -117,23 +428,13 @@ impl<'a, T: 'a> Placer<T> for &'a Arena<T> {
     type Place = ArenaPlace<'a, T>;
 
     fn make_place(self) -> Self::Place {
-        let mut chunks = self.chunks.borrow_mut();
-
-        // At this point, the current chunk must have free capacity.
-        // This precondition is maintained in the `finalize` method
-        let next_item_index = chunks.current.len();
-
-        let next_item_ptr = unsafe {
-            // Move the pointer for one more space, and then get the
-            // (uninitialized!) location for the next piece
-            // TODO:: How does this handle panics after this point?
-            chunks.current.set_len(next_item_index + 1);
-            chunks.current.get_unchecked_mut(next_item_index)
-        };
+        if self.ptr.get() == self.end.get() {
+            self.grow(1);
+        }
 
         ArenaPlace {
             arena: self,
-            ptr: next_item_ptr,
+            ptr: self.ptr.get(),
         }
     }
 }
@@ -153,40 +454,18 @@ impl<'a, T: 'a> InPlace<T> for ArenaPlace<'a, T> {
     type Owner = &'a mut T;
 
     unsafe fn finalize(self) -> Self::Owner {
-        let mut chunks = self.arena.chunks.borrow_mut();
-
-        // Maintain the precondition that there will always be space
-        // when we try to allocate next.
-        if chunks.current.len() == chunks.current.capacity() {
-            chunks.grow();
-        }
-
         let ptr = self.ptr;
 
-        // Forgetting the reference to the arena is safe as something
-        // else owns it. This prevents the `Drop` implementation from
-        // running, which would otherwise roll back the allocation.
+        // Commit the bump allocation now that the value was written
+        // successfully.
+        self.arena.ptr.set(ptr.offset(1));
+
         mem::forget(self);
 
-        // Extend the lifetime to that of `arena`.
-        //
-        // This is OK because we’re careful to never move items as we
-        // never push to the inner `Vec`s beyond their initial
-        // capacity.
-        //
-        // The returned reference is unique (`&mut`) because the
-        // `Arena` never gives away references to existing items.
-        mem::transmute(ptr)
+        &mut *ptr
     }
 }
 
-impl<'a, T: 'a> Drop for ArenaPlace<'a, T> {
-    fn drop(&mut self) {
-        // We are only dropped if the placement fails. That means we
-        // need to roll back the allocation so that the destructor of
-        // T is not run on uninitialized memory.
-        let mut chunks = self.arena.chunks.borrow_mut();
-        let len = chunks.current.len();
-        unsafe { chunks.current.set_len(len - 1) };
-    }
-}
+// No `Drop` impl is needed for `ArenaPlace`: the bump cursor is only
+// advanced in `finalize`, so if placement fails and this is dropped instead,
+// there's nothing to roll back.
@@ -0,0 +1,208 @@
+use std::cell::Cell;
+use {Arena, AsArena, DroplessArena};
+
+#[test]
+fn cyclic_drop_types_are_allowed() {
+    // A node that points at a sibling allocated in the same arena, and
+    // records when it's dropped. Without the `#[may_dangle]` eyepatch on
+    // `Arena`'s `Drop` impl, the borrow checker would reject building this
+    // cycle at all, since it would assume `Arena::drop` might read through
+    // the `&'a` fields of the nodes it's about to drop.
+    //
+    // `DropOrder` gets its own lifetime `'b`, separate from the `'a` used
+    // for the sibling self-reference: tying the Drop-accessed data to the
+    // same self-referential `'a` makes the arena's own lifetime unnameable
+    // (it would have to both start before, and be equal to, itself), so
+    // dropping the arena is rejected by the borrow checker regardless of
+    // the eyepatch. Keeping them distinct is what actually lets this
+    // compile.
+    struct DropOrder<'b>(&'b Cell<u32>, u32);
+
+    impl<'b> Drop for DropOrder<'b> {
+        fn drop(&mut self) {
+            let order = self.0.get();
+            self.0.set(order + 1);
+            assert_eq!(order, self.1);
+        }
+    }
+
+    struct CyclicNode<'a, 'b> {
+        _drop_order: DropOrder<'b>,
+        sibling: Cell<Option<&'a CyclicNode<'a, 'b>>>,
+    }
+
+    let drop_counter = Cell::new(0);
+    {
+        let arena = Arena::new();
+
+        let a = arena.alloc(CyclicNode {
+            _drop_order: DropOrder(&drop_counter, 0),
+            sibling: Cell::new(None),
+        });
+        let b = arena.alloc(CyclicNode {
+            _drop_order: DropOrder(&drop_counter, 1),
+            sibling: Cell::new(None),
+        });
+
+        a.sibling.set(Some(&*b));
+        b.sibling.set(Some(&*a));
+    }
+
+    assert_eq!(drop_counter.get(), 2);
+}
+
+#[test]
+fn alloc_extend_fills_in_order() {
+    let arena = Arena::new();
+    let slice = arena.alloc_extend(0..5);
+    assert_eq!(slice, &[0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn alloc_extend_past_a_lying_size_hint() {
+    // An iterator whose `size_hint` undersells how many items it actually
+    // yields, forcing `alloc_extend` to grow mid-loop and relocate the
+    // partially-filled tail into the new chunk via `grow_carrying`.
+    struct Dishonest(u32);
+
+    impl Iterator for Dishonest {
+        type Item = u32;
+
+        fn next(&mut self) -> Option<u32> {
+            if self.0 < 10 {
+                self.0 += 1;
+                Some(self.0)
+            } else {
+                None
+            }
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            (0, None)
+        }
+    }
+
+    let arena = Arena::with_capacity(1);
+    let slice = arena.alloc_extend(Dishonest(0));
+    assert_eq!(slice, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+}
+
+#[test]
+fn dropless_arena_alloc_and_alloc_slice() {
+    let arena = DroplessArena::new();
+
+    let a = arena.alloc(1u32);
+    let b = arena.alloc(2u32);
+    assert_eq!(*a, 1);
+    assert_eq!(*b, 2);
+
+    let s = arena.alloc_slice(&[1u8, 2, 3, 4]);
+    assert_eq!(s, &[1, 2, 3, 4]);
+}
+
+#[test]
+fn dropless_arena_grows_across_many_chunks() {
+    let arena = DroplessArena::new();
+    let mut refs = Vec::new();
+
+    for i in 0..10_000u32 {
+        refs.push(arena.alloc(i));
+    }
+
+    for (i, r) in refs.into_iter().enumerate() {
+        assert_eq!(*r, i as u32);
+    }
+}
+
+#[test]
+fn dropless_arena_allocates_a_zst_without_crashing() {
+    // Regression test: allocating a ZST as the very first allocation from a
+    // fresh arena used to dereference the null `start`/`end` cursors.
+    let arena = DroplessArena::new();
+    let _: &mut () = arena.alloc(());
+
+    let s: &mut [()] = arena.alloc_slice(&[(), (), ()]);
+    assert_eq!(s.len(), 3);
+}
+
+#[test]
+fn len_tracks_allocations_across_chunks() {
+    let arena = Arena::with_capacity(1);
+    assert_eq!(arena.len(), 0);
+
+    for i in 0..100 {
+        arena.alloc(i);
+    }
+
+    assert_eq!(arena.len(), 100);
+}
+
+#[test]
+fn iter_mut_visits_items_in_allocation_order_and_allows_mutation() {
+    let mut arena = Arena::with_capacity(1);
+    for i in 0..100 {
+        arena.alloc(i);
+    }
+
+    for item in arena.iter_mut() {
+        *item *= 2;
+    }
+
+    let collected: Vec<i32> = arena.iter_mut().map(|item| *item).collect();
+    let expected: Vec<i32> = (0..100).map(|i| i * 2).collect();
+    assert_eq!(collected, expected);
+}
+
+#[test]
+fn into_iter_consumes_the_arena_in_allocation_order() {
+    let arena = Arena::with_capacity(1);
+    for i in 0..100 {
+        arena.alloc(i);
+    }
+
+    let collected: Vec<i32> = arena.into_iter().collect();
+    let expected: Vec<i32> = (0..100).collect();
+    assert_eq!(collected, expected);
+}
+
+#[test]
+fn arena_ref_resolves_through_growth() {
+    let arena = Arena::with_capacity(1);
+
+    // Allocate enough handles to force several chunk growths, so `get`
+    // has to find handles living in both `rest` and `current`.
+    let handles: Vec<_> = (0..100).map(|i| arena.alloc_ref(i)).collect();
+
+    for (i, handle) in handles.iter().enumerate() {
+        assert_eq!(*arena.get(handle), i as i32);
+    }
+}
+
+#[test]
+#[should_panic(expected = "does not belong to this Arena")]
+fn verify_ownership_rejects_a_handle_from_another_arena() {
+    let a = Arena::with_capacity(1);
+    let b = Arena::with_capacity(1);
+
+    let handle = a.alloc_ref(1);
+    b.get(&handle);
+}
+
+#[test]
+fn arena_allocates_zero_sized_types() {
+    // Regression test: `ptr::offset` is a no-op for a zero-size step, so
+    // the pointer-bump cursor can't tell "full" from "empty" for a ZST,
+    // and used to overflow in `grow_carrying`'s capacity doubling on the
+    // very first `alloc`.
+    let arena = Arena::new();
+    assert_eq!(arena.len(), 0);
+
+    for _ in 0..10 {
+        arena.alloc(());
+    }
+    assert_eq!(arena.len(), 10);
+
+    let slice = arena.alloc_extend((0..10).map(|_| ()));
+    assert_eq!(slice.len(), 10);
+    assert_eq!(arena.len(), 20);
+}